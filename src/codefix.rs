@@ -0,0 +1,243 @@
+//! Apply `rustc --error-format=json` diagnostics as structured edits.
+//!
+//! This builds a [`CodeFix`] on top of [`Data`], translating the
+//! machine-readable suggestions rustc emits into
+//! [`replace_range_unless_touched`][Data::replace_range_unless_touched]
+//! calls, so overlapping suggestions are rejected instead of silently
+//! corrupting the file.
+
+use std::collections::HashMap;
+
+use serde::Deserialize;
+
+use super::{Data, Error};
+
+/// How confident rustc is that applying a suggestion won't break the code.
+///
+/// Mirrors `rustc_errors::Applicability`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub enum Applicability {
+    /// The suggestion is definitely what the user intended.
+    MachineApplicable,
+    /// The suggestion contains placeholders that have to be filled in by
+    /// hand before the result is valid code.
+    HasPlaceholders,
+    /// The suggestion may be what the user intended, but it is uncertain.
+    MaybeIncorrect,
+    /// rustc did not say how applicable the suggestion is.
+    Unspecified,
+}
+
+/// The `code` field of a [`Diagnostic`].
+#[derive(Debug, Clone, Deserialize)]
+pub struct DiagnosticCode {
+    /// The error code, e.g. `"E0308"`.
+    pub code: String,
+    /// A longer explanation of the error code, if any.
+    pub explanation: Option<String>,
+}
+
+/// A source span referenced by a [`Diagnostic`], as emitted by rustc's JSON
+/// output.
+#[derive(Debug, Clone, Deserialize)]
+pub struct DiagnosticSpan {
+    /// Path of the file this span points into.
+    pub file_name: String,
+    /// Byte offset of the start of the span.
+    pub byte_start: usize,
+    /// Byte offset of the end of the span.
+    pub byte_end: usize,
+    /// Text that rustc suggests replacing this span with, if any.
+    pub suggested_replacement: Option<String>,
+    /// How confident rustc is in `suggested_replacement`.
+    pub suggestion_applicability: Option<Applicability>,
+}
+
+/// A single diagnostic message from `rustc --error-format=json`.
+///
+/// Only the fields this crate cares about are modeled; any other fields
+/// present in the JSON are ignored.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Diagnostic {
+    /// The human-readable diagnostic message.
+    pub message: String,
+    /// The lint or error code, if any.
+    pub code: Option<DiagnosticCode>,
+    /// Spans this diagnostic points at.
+    #[serde(default)]
+    pub spans: Vec<DiagnosticSpan>,
+    /// Diagnostics nested under this one, e.g. help messages.
+    #[serde(default)]
+    pub children: Vec<Diagnostic>,
+}
+
+impl Diagnostic {
+    fn collect_suggestions(&self, out: &mut Vec<Suggestion>) {
+        for span in &self.spans {
+            if let Some(replacement) = &span.suggested_replacement {
+                out.push(Suggestion {
+                    file_name: span.file_name.clone(),
+                    byte_start: span.byte_start,
+                    byte_end: span.byte_end,
+                    replacement: replacement.clone(),
+                    applicability: span.suggestion_applicability,
+                });
+            }
+        }
+
+        for child in &self.children {
+            child.collect_suggestions(out);
+        }
+    }
+}
+
+/// A concrete edit extracted from a [`Diagnostic`]: replace the bytes in
+/// `file_name[byte_start..byte_end]` with `replacement`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Suggestion {
+    /// Path of the file this suggestion applies to.
+    pub file_name: String,
+    /// Byte offset of the start of the replaced range.
+    pub byte_start: usize,
+    /// Byte offset of the end of the replaced range.
+    pub byte_end: usize,
+    /// The text to replace the range with.
+    pub replacement: String,
+    /// How confident rustc is in this suggestion.
+    pub applicability: Option<Applicability>,
+}
+
+/// Parse the (newline-delimited) output of `rustc --error-format=json` and
+/// collect every suggestion it contains, including ones nested in child
+/// diagnostics.
+///
+/// Lines that aren't valid diagnostic JSON (e.g. blank lines, or other
+/// cargo/rustc chatter mixed into the stream) are skipped.
+pub fn parse_suggestions_from_json(json: &str) -> Vec<Suggestion> {
+    let mut suggestions = Vec::new();
+    for line in json.lines() {
+        if let Ok(diagnostic) = serde_json::from_str::<Diagnostic>(line) {
+            diagnostic.collect_suggestions(&mut suggestions);
+        }
+    }
+    suggestions
+}
+
+/// Applies [`Suggestion`]s across a set of files, one [`Data`] per file.
+#[derive(Debug, Clone, Default)]
+pub struct CodeFix {
+    files: HashMap<String, Data>,
+}
+
+impl CodeFix {
+    /// Create a `CodeFix` from the current contents of the files it will
+    /// apply suggestions to.
+    pub fn new(files: impl IntoIterator<Item = (String, Vec<u8>)>) -> Self {
+        CodeFix {
+            files: files
+                .into_iter()
+                .map(|(name, data)| (name, Data::new(&data)))
+                .collect(),
+        }
+    }
+
+    /// Apply a single suggestion, erroring if the suggestion's range
+    /// overlaps a part of the file that was already changed.
+    pub fn apply(&mut self, suggestion: &Suggestion) -> Result<(), Error> {
+        let data = self
+            .files
+            .get_mut(&suggestion.file_name)
+            .ok_or_else(|| Error::UnknownFile(suggestion.file_name.clone()))?;
+
+        data.replace_range_unless_touched(
+            suggestion.byte_start..suggestion.byte_end,
+            suggestion.replacement.as_bytes(),
+        )
+    }
+
+    /// Render the current contents of a single file, if it's part of this
+    /// `CodeFix`.
+    pub fn to_vec(&self, file_name: &str) -> Option<Vec<u8>> {
+        self.files.get(file_name).map(Data::to_vec)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_suggestions_including_nested_children_and_skips_bad_lines() {
+        let json = concat!(
+            "not valid json, should just be skipped\n",
+            r#"{"message": "unused import", "code": null, "spans": [], "children": ["#,
+            r#"{"message": "remove the import", "code": null, "spans": ["#,
+            r#"{"file_name": "src/lib.rs", "byte_start": 0, "byte_end": 3, "#,
+            r#""suggested_replacement": "", "suggestion_applicability": "MachineApplicable"}"#,
+            r#"], "children": []}]}"#,
+            "\n",
+        );
+
+        let suggestions = parse_suggestions_from_json(json);
+
+        assert_eq!(
+            vec![Suggestion {
+                file_name: "src/lib.rs".to_string(),
+                byte_start: 0,
+                byte_end: 3,
+                replacement: "".to_string(),
+                applicability: Some(Applicability::MachineApplicable),
+            }],
+            suggestions,
+        );
+    }
+
+    #[test]
+    fn apply_rejects_an_unknown_file() {
+        let mut fix = CodeFix::new(vec![("src/lib.rs".to_string(), b"foo bar".to_vec())]);
+
+        let suggestion = Suggestion {
+            file_name: "src/other.rs".to_string(),
+            byte_start: 0,
+            byte_end: 3,
+            replacement: "baz".to_string(),
+            applicability: None,
+        };
+
+        assert_eq!(
+            Err(Error::UnknownFile("src/other.rs".to_string())),
+            fix.apply(&suggestion),
+        );
+    }
+
+    #[test]
+    fn apply_rejects_the_second_of_two_overlapping_suggestions() {
+        let mut fix = CodeFix::new(vec![("src/lib.rs".to_string(), b"foo bar baz".to_vec())]);
+
+        let first = Suggestion {
+            file_name: "src/lib.rs".to_string(),
+            byte_start: 4,
+            byte_end: 7,
+            replacement: "BAR".to_string(),
+            applicability: Some(Applicability::MachineApplicable),
+        };
+        let second = Suggestion {
+            file_name: "src/lib.rs".to_string(),
+            byte_start: 4,
+            byte_end: 7,
+            replacement: "quux".to_string(),
+            applicability: Some(Applicability::MachineApplicable),
+        };
+
+        fix.apply(&first).unwrap();
+        assert_eq!(
+            Err(Error::MaybeAlreadyReplaced(4..7)),
+            fix.apply(&second),
+        );
+        assert_eq!(
+            Some(b"foo BAR baz".to_vec()),
+            fix.to_vec("src/lib.rs"),
+        );
+    }
+}
@@ -0,0 +1,71 @@
+//! Compare a [`Data`]'s current state to the original bytes it was built
+//! from.
+
+use std::ops::Range;
+
+use super::{Data, State};
+
+/// A single span-level difference between the original data and its
+/// current state, as produced by [`Data::changes`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Change<'a> {
+    /// The byte range this change covers in the *original* data.
+    pub range_in_original: Range<usize>,
+    /// The original bytes covering `range_in_original`; empty for a pure
+    /// insertion.
+    pub old: &'a [u8],
+    /// The bytes that now render in place of `old`; empty for a deletion.
+    pub new: &'a [u8],
+}
+
+impl Change<'_> {
+    /// Whether this change leaves the data untouched, i.e. `old == new`.
+    pub fn is_unchanged(&self) -> bool {
+        self.old == self.new
+    }
+}
+
+impl Data {
+    /// Walk the current state of this `Data` and report, span by span, how
+    /// it differs from the original bytes it was created from.
+    ///
+    /// Untouched spans report `old == new`; replacements report both sides
+    /// non-empty and different; insertions report an empty `old`;
+    /// deletions (a range replaced with nothing) report an empty `new`.
+    pub fn changes(&self) -> Vec<Change<'_>> {
+        self.parts
+            .iter()
+            .map(|part| {
+                let old = &self.original[part.start..part.end];
+                let new: &[u8] = match &part.data {
+                    State::Initial => old,
+                    State::Replaced(bytes) => bytes,
+                };
+                Change {
+                    range_in_original: part.start..part.end,
+                    old,
+                    new,
+                }
+            })
+            .collect()
+    }
+
+    /// Render a unified diff between the original bytes and the data's
+    /// current state, so callers can preview or record what a batch of
+    /// edits produced before committing [`to_vec`][Data::to_vec] to disk.
+    ///
+    /// Both sides are decoded as (possibly lossy) UTF-8 text, so the result
+    /// reads like a normal source-code patch; use [`changes`][Data::changes]
+    /// instead if you need exact byte-for-byte spans.
+    pub fn unified_diff(&self) -> String {
+        let old = String::from_utf8_lossy(&self.original);
+        let current = self.to_vec();
+        let new = String::from_utf8_lossy(&current);
+
+        super::similar::TextDiff::from_lines(old.as_ref(), new.as_ref())
+            .unified_diff()
+            .context_radius(3)
+            .header("original", "current")
+            .to_string()
+    }
+}
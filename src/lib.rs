@@ -2,62 +2,131 @@
 //! replacement of parts of its content, with the ability to prevent changing
 //! the same parts multiple times.
 
-#[deny(missing_docs)]
+#![deny(missing_docs)]
 
-#[macro_use]
-extern crate failure;
+extern crate serde;
+extern crate serde_json;
+extern crate similar;
 #[cfg(test)]
-#[macro_use]
 extern crate proptest;
 
-use failure::Error;
+use std::fmt;
 use std::ops::Range;
 use std::rc::Rc;
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+mod codefix;
+mod diff;
+
+pub use codefix::{
+    Applicability, CodeFix, Diagnostic, DiagnosticCode, DiagnosticSpan, Suggestion,
+    parse_suggestions_from_json,
+};
+pub use diff::Change;
+
+/// Errors that can occur while replacing parts of a [`Data`]'s content.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Error {
+    /// The given range has its `start` after its `end`.
+    InvalidRange(Range<usize>),
+    /// The given range reaches past the end of the original data, whose
+    /// length is given as the second field.
+    DataLengthExceeded(Range<usize>, usize),
+    /// The given range overlaps a part of the data that was already
+    /// replaced earlier, and `replace_range_unless_touched` was used.
+    MaybeAlreadyReplaced(Range<usize>),
+    /// A suggestion referenced a file that isn't part of the `CodeFix` it
+    /// was applied to.
+    UnknownFile(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::InvalidRange(range) => {
+                write!(f, "invalid range {:?}: start is after end", range)
+            }
+            Error::DataLengthExceeded(range, len) => write!(
+                f,
+                "range {:?} exceeds the original data's length of {}",
+                range, len
+            ),
+            Error::MaybeAlreadyReplaced(range) => write!(
+                f,
+                "can't replace range {:?}: part of it was already replaced",
+                range
+            ),
+            Error::UnknownFile(file_name) => {
+                write!(f, "{} is not part of this CodeFix", file_name)
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// What a [`Span`] renders as.
+#[derive(Debug, Clone, PartialEq, Eq)]
 enum State {
-    Untouched,
-    Touched,
+    /// Render `original[start..end]` unchanged.
+    Initial,
+    /// Render these bytes instead of `original[start..end]`.
+    Replaced(Box<[u8]>),
 }
 
+/// A half-open `[start, end)` slice of the original data, together with
+/// what it should currently render as.
 #[derive(Debug, Clone, PartialEq, Eq)]
 struct Span {
-    state: State,
-    range: Range<usize>,
-    data: Rc<[u8]>,
+    start: usize,
+    end: usize,
+    data: State,
 }
 
 /// A container that allows easily replacing chunks of its data
-#[derive(Debug, Clone, Default)]
+#[derive(Debug, Clone)]
 pub struct Data {
+    original: Rc<[u8]>,
     parts: Vec<Span>,
 }
 
+impl Default for Data {
+    fn default() -> Self {
+        Data::new(&[])
+    }
+}
+
 impl Data {
     /// Create a new data container from a slice of bytes
     pub fn new(data: &[u8]) -> Self {
-        if data.is_empty() {
-            return Data::default();
+        let original: Rc<[u8]> = data.into();
+        if original.is_empty() {
+            return Data {
+                original,
+                parts: Vec::new(),
+            };
         }
 
+        let end = original.len();
         Data {
+            original,
             parts: vec![Span {
-                state: State::Untouched,
-                range: 0..data.len(),
-                data: data.into(),
+                start: 0,
+                end,
+                data: State::Initial,
             }],
         }
     }
 
     /// Render this data as a vector of bytes
     pub fn to_vec(&self) -> Vec<u8> {
-        self.parts
-            .iter()
-            .map(|x| &x.data)
-            .fold(Vec::new(), |mut acc, d| {
-                acc.extend(d.iter());
-                acc
-            })
+        self.parts.iter().fold(Vec::new(), |mut acc, part| {
+            match &part.data {
+                State::Initial => acc.extend_from_slice(&self.original[part.start..part.end]),
+                State::Replaced(bytes) => acc.extend_from_slice(bytes),
+            }
+            acc
+        })
     }
 
     /// Replace a chunk of data with the given slice, erroring when this part
@@ -72,85 +141,166 @@ impl Data {
 
     /// Replace a chunk of data with a given slice and the option to return an
     /// error if this part of the data was already changed earlier.
+    ///
+    /// `range` is always relative to the *original* buffer passed to
+    /// [`Data::new`], never to the current, possibly-grown length returned by
+    /// [`Data::to_vec`]. In particular, appending after the original data
+    /// means inserting at `original.len()`, not at `to_vec().len()`.
+    ///
+    /// `error_if_touched` only controls what happens when `range` fully
+    /// covers a span that was already replaced (e.g. replacing the same
+    /// range twice, or inserting twice at the same offset). A `range` that
+    /// partially overlaps an already-replaced
+    /// span always errors with [`Error::MaybeAlreadyReplaced`], regardless
+    /// of `error_if_touched`, since applying it would silently discard part
+    /// of the earlier replacement.
     pub fn replace_range(
         &mut self,
         range: Range<usize>,
         data: &[u8],
         error_if_touched: bool,
     ) -> Result<(), Error> {
-        if range.end == 0 {
-            return Ok(());
+        if range.start > range.end {
+            return Err(Error::InvalidRange(range));
         }
 
-        let new_parts = {
-            use std::cmp::min;
-
-            let start = self.parts
-                .iter()
-                .position(|x| x.range.start <= range.start)
-                .ok_or_else(|| format_err!("No part found that contains range {:?}", range))?;
-            let end = self.parts.iter().rposition(|x| x.range.end >= range.end);
-
-            if error_if_touched {
-                let end = if let Some(end) = end {
-                    end + 1
-                } else {
-                    self.parts.len()
-                };
-                let any_touched = self.parts[start..end]
-                    .iter()
-                    .any(|p| p.state == State::Touched);
-                ensure!(
-                    !any_touched,
-                    "can't replace segments that were replaced previously"
-                );
-            }
+        let original_len = self.original.len();
+        if range.end > original_len {
+            return Err(Error::DataLengthExceeded(range, original_len));
+        }
 
-            let mut res = Vec::with_capacity(self.parts.len());
-            if start > 0 {
-                res.extend(self.parts[..start.saturating_sub(1)].iter().cloned());
-            }
+        if range.start == range.end {
+            return self.insert_at(range.start, data, error_if_touched);
+        }
 
-            let start_part = &self.parts[start];
+        let start = self.parts
+            .iter()
+            .position(|part| part.end > range.start)
+            .ok_or_else(|| Error::DataLengthExceeded(range.clone(), original_len))?;
+        let end = self.parts
+            .iter()
+            .rposition(|part| part.start < range.end)
+            .ok_or_else(|| Error::DataLengthExceeded(range.clone(), original_len))?;
 
-            let start_range_end = range.start.saturating_sub(start_part.range.start);
+        let any_touched = self.parts[start..=end]
+            .iter()
+            .any(|part| matches!(part.data, State::Replaced(_)));
+        if error_if_touched && any_touched {
+            return Err(Error::MaybeAlreadyReplaced(range));
+        }
 
-            if start_range_end > 0 {
-                let data = start_part.data[..min(start_range_end, start_part.data.len())].into();
-                res.push(Span {
-                    state: start_part.state,
-                    range: start_part.range.start..range.start,
-                    data,
-                });
-            }
+        // Even when `error_if_touched` is false, a `range` that only
+        // partially overlaps an already-replaced span can't be applied
+        // without silently discarding the part of that replacement outside
+        // `range` -- so that case always errors.
+        let partially_overlaps_replaced = self.parts[start..=end].iter().any(|part| {
+            matches!(part.data, State::Replaced(_))
+                && (part.start < range.start || part.end > range.end)
+        });
+        if partially_overlaps_replaced {
+            return Err(Error::MaybeAlreadyReplaced(range));
+        }
+
+        let mut new_parts = Vec::with_capacity(self.parts.len() + 2);
+        new_parts.extend_from_slice(&self.parts[..start]);
 
-            res.push(Span {
-                state: State::Touched,
-                range: range.start..range.end,
-                data: data.into(),
+        let start_part = &self.parts[start];
+        if start_part.start < range.start && start_part.data == State::Initial {
+            new_parts.push(Span {
+                start: start_part.start,
+                end: range.start,
+                data: State::Initial,
             });
+        }
 
-            if let Some(end) = end {
-                let end_part = &self.parts[end];
-                if !end_part.data.is_empty() {
-                    res.push(Span {
-                        state: end_part.state,
-                        range: range.end..end_part.range.end,
-                        data: end_part.data[min(
-                            range.end.saturating_sub(end_part.range.start),
-                            end_part.data.len().saturating_sub(1),
-                        )..]
-                            .into(),
-                    });
-
-                    res.extend(self.parts[end + 1..].iter().cloned());
+        new_parts.push(Span {
+            start: range.start,
+            end: range.end,
+            data: State::Replaced(data.into()),
+        });
+
+        let end_part = &self.parts[end];
+        if end_part.end > range.end && end_part.data == State::Initial {
+            new_parts.push(Span {
+                start: range.end,
+                end: end_part.end,
+                data: State::Initial,
+            });
+        }
+
+        new_parts.extend_from_slice(&self.parts[end + 1..]);
+
+        self.parts = new_parts;
+
+        Ok(())
+    }
+
+    /// Splice `data` into the buffer at `at` without consuming any original
+    /// bytes, handling offset `0` (prepend), offset `len` (append), and
+    /// offsets strictly inside an untouched span (which gets split).
+    ///
+    /// Like [`Data::replace_range`], `at` is relative to the original
+    /// buffer's length, not the rendered length from [`Data::to_vec`].
+    fn insert_at(&mut self, at: usize, data: &[u8], error_if_touched: bool) -> Result<(), Error> {
+        if let Some(index) = self.parts
+            .iter()
+            .position(|part| part.start < at && at < part.end)
+        {
+            let part = self.parts[index].clone();
+            if part.data == State::Initial {
+                self.parts.splice(
+                    index..=index,
+                    vec![
+                        Span {
+                            start: part.start,
+                            end: at,
+                            data: State::Initial,
+                        },
+                        Span {
+                            start: at,
+                            end: part.end,
+                            data: State::Initial,
+                        },
+                    ],
+                );
+            } else {
+                // `at` lies strictly inside an already-replaced span.
+                return Err(Error::MaybeAlreadyReplaced(at..at));
+            }
+        }
+
+        // Find the first span after `at`, skipping past any insertions
+        // already sitting at this exact offset so that repeated insertions
+        // render in the order they were made, and noting along the way
+        // whether one of them should conflict under `error_if_touched`.
+        let mut index = self.parts.len();
+        let mut touched_at_point = false;
+        for (i, part) in self.parts.iter().enumerate() {
+            if part.start < at {
+                continue;
+            }
+            if part.start == at && part.end == at {
+                if matches!(part.data, State::Replaced(_)) {
+                    touched_at_point = true;
                 }
+                continue;
             }
+            index = i;
+            break;
+        }
 
-            res
-        };
+        if error_if_touched && touched_at_point {
+            return Err(Error::MaybeAlreadyReplaced(at..at));
+        }
 
-        self.parts = new_parts;
+        self.parts.insert(
+            index,
+            Span {
+                start: at,
+                end: at,
+                data: State::Replaced(data.into()),
+            },
+        );
 
         Ok(())
     }
@@ -169,13 +319,13 @@ mod tests {
     fn replace_some_stuff() {
         let mut d = Data::new(b"foo bar baz");
 
-        d.replace_range(4..6, b"lol", false).unwrap();
+        d.replace_range(4..7, b"lol", false).unwrap();
         assert_eq!("foo lol baz", str(&d.to_vec()));
 
-        d.replace_range(4..6, b"lol", false).unwrap();
+        d.replace_range(4..7, b"lol", false).unwrap();
         assert_eq!("foo lol baz", str(&d.to_vec()));
 
-        d.replace_range(4..6, b"foobar", false).unwrap();
+        d.replace_range(4..7, b"foobar", false).unwrap();
         assert_eq!("foo foobar baz", str(&d.to_vec()));
     }
 
@@ -186,7 +336,7 @@ mod tests {
         d.replace_range(6..11, b"lol", false).unwrap();
         assert_eq!("lorem\nlol\ndolor", str(&d.to_vec()));
 
-        d.replace_range(12..18, b"lol", false).unwrap();
+        d.replace_range(12..17, b"lol", false).unwrap();
         assert_eq!("lorem\nlol\nlol", str(&d.to_vec()));
     }
 
@@ -194,18 +344,140 @@ mod tests {
     fn broken_replacements() {
         let mut d = Data::new(b"foo");
 
-        d.replace_range_unless_touched(4..7, b"lol").unwrap();
-        assert_eq!("foolol", str(&d.to_vec()));
+        assert_eq!(
+            Err(Error::DataLengthExceeded(4..7, 3)),
+            d.replace_range_unless_touched(4..7, b"lol"),
+        );
+    }
+
+    #[test]
+    fn invalid_range_is_rejected() {
+        let mut d = Data::new(b"foo bar baz");
+        let (start, end) = (6, 4);
+
+        assert_eq!(
+            Err(Error::InvalidRange(start..end)),
+            d.replace_range(start..end, b"lol", false),
+        );
     }
 
     #[test]
     fn dont_replace_twice() {
-        let mut d = Data::new(b"foo");
+        let mut d = Data::new(b"foo bar baz");
 
         d.replace_range_unless_touched(4..7, b"lol").unwrap();
-        assert_eq!("foolol", str(&d.to_vec()));
+        assert_eq!("foo lol baz", str(&d.to_vec()));
         println!("{:?}", d);
-        assert!(d.replace_range_unless_touched(4..7, b"lol").is_err());
+        assert_eq!(
+            Err(Error::MaybeAlreadyReplaced(4..7)),
+            d.replace_range_unless_touched(4..7, b"lol"),
+        );
+    }
+
+    #[test]
+    fn partial_overlap_with_a_replaced_span_is_rejected_even_when_not_error_if_touched() {
+        let mut d = Data::new(b"abcdefghij");
+
+        d.replace_range(2..8, b"XYZ", false).unwrap();
+        assert_eq!(
+            Err(Error::MaybeAlreadyReplaced(4..6)),
+            d.replace_range(4..6, b"Q", false),
+        );
+        assert_eq!("abXYZij", str(&d.to_vec()));
+    }
+
+    #[test]
+    fn replace_range_at_a_boundary() {
+        let mut d = Data::new(b"foo bar baz");
+
+        d.replace_range(0..3, b"FOO", false).unwrap();
+        assert_eq!("FOO bar baz", str(&d.to_vec()));
+
+        d.replace_range(8..11, b"BAZ", false).unwrap();
+        assert_eq!("FOO bar BAZ", str(&d.to_vec()));
+    }
+
+    #[test]
+    fn insert_into_empty_data() {
+        let mut d = Data::new(b"");
+
+        d.replace_range(0..0, b"foo", false).unwrap();
+        assert_eq!("foo", str(&d.to_vec()));
+    }
+
+    #[test]
+    fn insert_at_start_and_end() {
+        let mut d = Data::new(b"bar");
+
+        d.replace_range(0..0, b"foo", false).unwrap();
+        assert_eq!("foobar", str(&d.to_vec()));
+
+        d.replace_range(3..3, b"baz", false).unwrap();
+        assert_eq!("foobarbaz", str(&d.to_vec()));
+    }
+
+    #[test]
+    fn insert_in_the_middle() {
+        let mut d = Data::new(b"foobaz");
+
+        d.replace_range(3..3, b"bar", false).unwrap();
+        assert_eq!("foobarbaz", str(&d.to_vec()));
+    }
+
+    #[test]
+    fn insertions_at_the_same_offset_keep_insertion_order() {
+        let mut d = Data::new(b"foo");
+
+        d.replace_range(3..3, b"bar", false).unwrap();
+        d.replace_range(3..3, b"baz", false).unwrap();
+        assert_eq!("foobarbaz", str(&d.to_vec()));
+    }
+
+    #[test]
+    fn conflicting_insertions_are_rejected() {
+        let mut d = Data::new(b"foo");
+
+        d.replace_range_unless_touched(3..3, b"bar").unwrap();
+        assert_eq!(
+            Err(Error::MaybeAlreadyReplaced(3..3)),
+            d.replace_range_unless_touched(3..3, b"baz"),
+        );
+    }
+
+    #[test]
+    fn changes_report_untouched_and_replaced_spans() {
+        let mut d = Data::new(b"foo bar baz");
+        d.replace_range(4..7, b"lol", false).unwrap();
+
+        let changes = d.changes();
+        assert_eq!(3, changes.len());
+
+        assert!(changes[0].is_unchanged());
+        assert_eq!(b"foo " as &[u8], changes[0].old);
+
+        assert_eq!(4..7, changes[1].range_in_original);
+        assert_eq!(b"bar" as &[u8], changes[1].old);
+        assert_eq!(b"lol" as &[u8], changes[1].new);
+        assert!(!changes[1].is_unchanged());
+
+        assert!(changes[2].is_unchanged());
+        assert_eq!(b" baz" as &[u8], changes[2].old);
+    }
+
+    #[test]
+    fn unified_diff_renders_a_replace_an_insert_and_a_delete() {
+        let mut d = Data::new(b"foo\nbar\nbaz\n");
+        d.replace_range(4..7, b"BAR", false).unwrap(); // replace
+        d.replace_range(12..12, b"qux\n", false).unwrap(); // insert
+        d.replace_range(0..4, b"", false).unwrap(); // delete
+
+        let diff = d.unified_diff();
+
+        assert!(diff.starts_with("--- original\n+++ current\n"));
+        assert!(diff.contains("-foo\n"));
+        assert!(diff.contains("-bar\n"));
+        assert!(diff.contains("+BAR\n"));
+        assert!(diff.contains("+qux\n"));
     }
 
     proptest! {